@@ -0,0 +1,594 @@
+//! Image dithering algorithms, reusable independently of the command-line binary.
+//!
+//! The entry point is [`Ditherer`], a builder that captures a [`Mode`] along with an optional
+//! error-diffusion factor and target palette, and applies it to any [`DynamicImage`].
+
+use clap::ValueEnum;
+use image::{DynamicImage, GenericImageView, Rgb, RgbImage};
+use rand::prelude::*;
+use rand::rngs::SmallRng;
+use std::fmt;
+use std::str::FromStr;
+
+/// The dithering algorithm to apply.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Mode {
+    Quantization,
+    Random,
+    Naive1d,
+    Naive2d,
+    FloydSteinberg,
+    FalseFloydSteinberg,
+    JarvisJudiceNinke,
+    Stucki,
+    Atkinson,
+    Burkes,
+    Sierra,
+    TwoRowSierra,
+    SierraLite,
+    Bayer2,
+    Bayer4,
+    Bayer8,
+}
+
+impl fmt::Display for Mode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let display_name = match self {
+            Mode::Quantization => "quantization",
+            Mode::Random => "random",
+            Mode::Naive1d => "naive1d",
+            Mode::Naive2d => "naive2d",
+            Mode::FloydSteinberg => "floyd-steinberg",
+            Mode::FalseFloydSteinberg => "false-floyd-steinberg",
+            Mode::JarvisJudiceNinke => "jarvis-judice-ninke",
+            Mode::Stucki => "stucki",
+            Mode::Atkinson => "atkinson",
+            Mode::Burkes => "burkes",
+            Mode::Sierra => "sierra",
+            Mode::TwoRowSierra => "two-row-sierra",
+            Mode::SierraLite => "sierra-lite",
+            Mode::Bayer2 => "bayer2",
+            Mode::Bayer4 => "bayer4",
+            Mode::Bayer8 => "bayer8",
+        };
+        write!(f, "{}", display_name)
+    }
+}
+
+pub type Delta = (i32, i32);
+pub type Ratio = (i16, i16);
+
+/// A single entry of an error-diffusion matrix: a neighbor offset and the fraction of the
+/// quantization error it receives.
+pub struct Coord(pub Delta, pub Ratio);
+
+/// Returns the error-diffusion matrix for `mode`, or an empty matrix for modes that do not
+/// diffuse error (quantization, random, and the ordered Bayer modes).
+pub fn diffusion_matrix(mode: Mode) -> Vec<Coord> {
+    match mode {
+        Mode::Naive1d => vec![Coord((1, 0), (1, 1))],
+        Mode::Naive2d => vec![Coord((1, 0), (1, 2)), Coord((0, 1), (1, 2))],
+        Mode::FloydSteinberg => vec![
+            Coord((1, 0), (7, 16)),
+            Coord((0, 1), (5, 16)),
+            Coord((1, 1), (1, 16)),
+            Coord((-1, 1), (3, 16)),
+        ],
+        Mode::FalseFloydSteinberg => vec![
+            Coord((1, 0), (3, 8)),
+            Coord((0, 1), (3, 8)),
+            Coord((1, 1), (2, 8)),
+        ],
+        Mode::JarvisJudiceNinke => vec![
+            Coord((1, 0), (7, 48)),
+            Coord((2, 0), (5, 48)),
+            Coord((0, 1), (7, 48)),
+            Coord((0, 2), (5, 48)),
+            Coord((1, 1), (5, 48)),
+            Coord((1, 2), (3, 48)),
+            Coord((2, 1), (3, 48)),
+            Coord((2, 2), (1, 48)),
+            Coord((-1, 1), (5, 48)),
+            Coord((-1, 2), (3, 48)),
+            Coord((-2, 1), (3, 48)),
+            Coord((-2, 2), (1, 48)),
+        ],
+        Mode::Stucki => vec![
+            Coord((1, 0), (8, 42)),
+            Coord((2, 0), (4, 42)),
+            Coord((0, 1), (8, 42)),
+            Coord((0, 2), (4, 42)),
+            Coord((1, 1), (4, 42)),
+            Coord((1, 2), (2, 42)),
+            Coord((2, 1), (2, 42)),
+            Coord((2, 2), (1, 42)),
+            Coord((-1, 1), (4, 42)),
+            Coord((-1, 2), (2, 42)),
+            Coord((-2, 1), (2, 42)),
+            Coord((-2, 2), (1, 42)),
+        ],
+        Mode::Atkinson => vec![
+            Coord((1, 0), (1, 8)),
+            Coord((2, 0), (1, 8)),
+            Coord((0, 1), (1, 8)),
+            Coord((0, 2), (1, 8)),
+            Coord((1, 1), (1, 8)),
+            Coord((-1, 1), (1, 8)),
+        ],
+        Mode::Burkes => vec![
+            Coord((1, 0), (8, 32)),
+            Coord((2, 0), (4, 32)),
+            Coord((0, 1), (8, 32)),
+            Coord((1, 1), (4, 32)),
+            Coord((2, 1), (2, 32)),
+            Coord((-1, 1), (4, 32)),
+            Coord((-2, 1), (2, 32)),
+        ],
+        Mode::Sierra => vec![
+            Coord((1, 0), (5, 32)),
+            Coord((2, 0), (3, 32)),
+            Coord((0, 1), (5, 32)),
+            Coord((0, 2), (3, 32)),
+            Coord((1, 1), (4, 32)),
+            Coord((2, 1), (2, 32)),
+            Coord((1, 2), (2, 32)),
+            Coord((-1, 1), (4, 32)),
+            Coord((-1, 2), (2, 32)),
+            Coord((-2, 1), (2, 32)),
+        ],
+        Mode::TwoRowSierra => vec![
+            Coord((1, 0), (4, 16)),
+            Coord((2, 0), (3, 16)),
+            Coord((0, 1), (3, 16)),
+            Coord((1, 1), (2, 16)),
+            Coord((2, 1), (1, 16)),
+            Coord((-1, 1), (2, 16)),
+            Coord((-2, 1), (1, 16)),
+        ],
+        Mode::SierraLite => vec![
+            Coord((1, 0), (2, 4)),
+            Coord((0, 1), (1, 4)),
+            Coord((-1, 1), (1, 4)),
+        ],
+        Mode::Quantization | Mode::Random | Mode::Bayer2 | Mode::Bayer4 | Mode::Bayer8 => vec![],
+    }
+}
+
+/// An ordered set of target colors that pixels are quantized toward.
+#[derive(Clone)]
+pub struct Palette(Vec<Rgb<u8>>);
+
+impl Palette {
+    /// Builds a palette from an explicit list of colors.
+    pub fn new(colors: Vec<Rgb<u8>>) -> Palette {
+        Palette(colors)
+    }
+
+    /// Builds a palette of `levels` evenly spaced grayscale shades, following
+    /// `round(i / (levels - 1) * 255)` for each step `i`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `levels` is less than 2, since fewer than two shades cannot be evenly spaced.
+    pub fn from_levels(levels: usize) -> Palette {
+        assert!(levels >= 2, "levels must be at least 2, got {}", levels);
+        let max_step = (levels - 1) as f64;
+        let colors = (0..levels)
+            .map(|i| {
+                let intensity = (i as f64 / max_step * 255.0).round() as u8;
+                Rgb([intensity, intensity, intensity])
+            })
+            .collect();
+        Palette(colors)
+    }
+
+    /// Returns the palette entry closest to `color` by squared Euclidean distance in RGB.
+    pub fn nearest(&self, color: Rgb<u8>) -> Rgb<u8> {
+        *self
+            .0
+            .iter()
+            .min_by_key(|candidate| squared_distance(color, **candidate))
+            .expect("palette is never empty")
+    }
+}
+
+impl FromStr for Palette {
+    type Err = String;
+
+    /// Parses a comma-separated list of 6-digit hex colors, e.g. `#000000,#ff0000,#ffffff`.
+    fn from_str(s: &str) -> Result<Palette, Self::Err> {
+        let colors = s
+            .split(',')
+            .map(parse_hex_color)
+            .collect::<Result<Vec<_>, _>>()?;
+        if colors.is_empty() {
+            Err("palette must contain at least one color".to_string())
+        } else {
+            Ok(Palette(colors))
+        }
+    }
+}
+
+fn parse_hex_color(s: &str) -> Result<Rgb<u8>, String> {
+    let hex = s.trim().trim_start_matches('#');
+    if hex.len() != 6 {
+        return Err(format!("'{}' is not a 6-digit hex color", s));
+    }
+    let channel = |range: std::ops::Range<usize>| {
+        u8::from_str_radix(&hex[range], 16).map_err(|_| format!("'{}' is not a valid hex color", s))
+    };
+    Ok(Rgb([channel(0..2)?, channel(2..4)?, channel(4..6)?]))
+}
+
+/// A configured dithering operation, built up fluently and applied with [`Ditherer::dither`].
+pub struct Ditherer {
+    mode: Mode,
+    factor: f64,
+    levels: usize,
+    palette: Option<Palette>,
+    serpentine: bool,
+}
+
+impl Ditherer {
+    /// Creates a ditherer for `mode` with a full diffusion factor, serpentine scanning enabled,
+    /// and the default two-level (black and white) palette.
+    pub fn new(mode: Mode) -> Ditherer {
+        Ditherer {
+            mode,
+            factor: 1.0,
+            levels: 2,
+            palette: None,
+            serpentine: true,
+        }
+    }
+
+    /// Sets how much quantization error is diffused, in `0.0..=1.0`. For the ordered (Bayer)
+    /// modes this scales the threshold bias instead, where `0.0` is plain quantization.
+    pub fn factor(mut self, factor: f64) -> Ditherer {
+        self.factor = factor;
+        self
+    }
+
+    /// Enables or disables serpentine (boustrophedon) scanning for error-diffusion modes, which
+    /// alternates the scan direction every row to suppress directional "worm" artifacts. Enabled
+    /// by default; has no effect on non-diffusion modes.
+    pub fn serpentine(mut self, serpentine: bool) -> Ditherer {
+        self.serpentine = serpentine;
+        self
+    }
+
+    /// Sets the number of evenly spaced grayscale levels to quantize toward, which must be at
+    /// least 2 (see [`Palette::from_levels`]). Ignored once a palette is set.
+    pub fn levels(mut self, levels: usize) -> Ditherer {
+        self.levels = levels;
+        self
+    }
+
+    /// Sets an explicit target palette, taking precedence over [`Ditherer::levels`].
+    pub fn palette(mut self, palette: Palette) -> Ditherer {
+        self.palette = Some(palette);
+        self
+    }
+
+    /// Applies the configured dithering to `img`, returning a new RGB image.
+    pub fn dither(&self, img: &DynamicImage) -> DynamicImage {
+        let mut rgb = img.to_rgb8();
+        let palette = self
+            .palette
+            .clone()
+            .unwrap_or_else(|| Palette::from_levels(self.levels));
+
+        match self.mode {
+            Mode::Quantization => quantization(&mut rgb, &palette),
+            Mode::Random => random_quantization(&mut rgb, &palette),
+            Mode::Bayer2 => ordered_dithering(&mut rgb, 2, &palette, self.factor),
+            Mode::Bayer4 => ordered_dithering(&mut rgb, 4, &palette, self.factor),
+            Mode::Bayer8 => ordered_dithering(&mut rgb, 8, &palette, self.factor),
+            mode => generic_dithering(
+                &mut rgb,
+                &diffusion_matrix(mode),
+                self.factor,
+                &palette,
+                self.serpentine,
+            ),
+        }
+
+        DynamicImage::ImageRgb8(rgb)
+    }
+}
+
+fn quantization(img: &mut RgbImage, palette: &Palette) {
+    let (width, height) = img.dimensions();
+
+    for y in 0..height {
+        for x in 0..width {
+            quantize_pixel(img, x, y, palette);
+        }
+    }
+}
+
+fn random_quantization(img: &mut RgbImage, palette: &Palette) {
+    let mut rng = SmallRng::from_entropy();
+    let (width, height) = img.dimensions();
+
+    for y in 0..height {
+        for x in 0..width {
+            quantize_pixel_with_rng(&mut rng, img, x, y, palette);
+        }
+    }
+}
+
+fn ordered_dithering(img: &mut RgbImage, n: u32, palette: &Palette, factor: f64) {
+    let matrix = bayer_matrix(n);
+    let (width, height) = img.dimensions();
+
+    // The threshold map biases each pixel up or down before it is snapped to the nearest palette
+    // entry; the magnitude of that bias spans one full step between evenly spaced levels, scaled
+    // by `factor`. Ordered dithering never mutates its neighbors, so we read and write each pixel
+    // in place. For a 2-level palette and a factor of 1.0 this reduces to the classic
+    // `255 if intensity/255 >= t`.
+    let steps = palette.0.len().saturating_sub(1).max(1) as f64;
+    let spread = factor * 255.0 / steps;
+
+    for y in 0..height {
+        for x in 0..width {
+            let threshold =
+                (f64::from(matrix[(x % n) as usize][(y % n) as usize]) + 0.5) / f64::from(n * n);
+            let bias = (0.5 - threshold) * spread;
+
+            let old_pixel = *img.get_pixel(x, y);
+            let biased = Rgb([
+                coerce_to_u8((f64::from(old_pixel[0]) + bias).round() as i16),
+                coerce_to_u8((f64::from(old_pixel[1]) + bias).round() as i16),
+                coerce_to_u8((f64::from(old_pixel[2]) + bias).round() as i16),
+            ]);
+            img.put_pixel(x, y, palette.nearest(biased));
+        }
+    }
+}
+
+// Builds an `n x n` Bayer threshold matrix, where `n` must be a power of two. The recurrence
+// starts from `M1 = [[0, 2], [3, 1]]` and doubles each side via
+// `M_{2n}[i][j] = 4 * M_n[i mod n][j mod n] + c`, where `c` is `0`, `2`, `3`, `1` for the
+// top-left, top-right, bottom-left, and bottom-right quadrants respectively.
+fn bayer_matrix(n: u32) -> Vec<Vec<u32>> {
+    let mut matrix = vec![vec![0, 2], vec![3, 1]];
+
+    while (matrix.len() as u32) < n {
+        let half = matrix.len();
+        let size = half * 2;
+        let mut next = vec![vec![0; size]; size];
+        for i in 0..size {
+            for j in 0..size {
+                let base = 4 * matrix[i % half][j % half];
+                let quadrant_constant = match (i < half, j < half) {
+                    (true, true) => 0,
+                    (true, false) => 2,
+                    (false, true) => 3,
+                    (false, false) => 1,
+                };
+                next[i][j] = base + quadrant_constant;
+            }
+        }
+        matrix = next;
+    }
+
+    matrix
+}
+
+fn quantize_pixel(img: &mut RgbImage, x: u32, y: u32, palette: &Palette) -> [i16; 3] {
+    let old_pixel = *img.get_pixel(x, y);
+    let new_pixel = palette.nearest(old_pixel);
+    img.put_pixel(x, y, new_pixel);
+
+    channel_error(old_pixel, new_pixel)
+}
+
+fn quantize_pixel_with_rng(
+    mut rng: impl rand::Rng,
+    img: &mut RgbImage,
+    x: u32,
+    y: u32,
+    palette: &Palette,
+) -> [i16; 3] {
+    let old_pixel = *img.get_pixel(x, y);
+    // Perturb each channel by uniform noise before snapping, spreading pixels randomly across
+    // nearby palette entries.
+    let jittered = Rgb([
+        coerce_to_u8(i16::from(old_pixel[0]) + rng.gen_range(-128..128)),
+        coerce_to_u8(i16::from(old_pixel[1]) + rng.gen_range(-128..128)),
+        coerce_to_u8(i16::from(old_pixel[2]) + rng.gen_range(-128..128)),
+    ]);
+    let new_pixel = palette.nearest(jittered);
+    img.put_pixel(x, y, new_pixel);
+
+    channel_error(old_pixel, new_pixel)
+}
+
+fn channel_error(old_pixel: Rgb<u8>, new_pixel: Rgb<u8>) -> [i16; 3] {
+    [
+        i16::from(old_pixel[0]) - i16::from(new_pixel[0]),
+        i16::from(old_pixel[1]) - i16::from(new_pixel[1]),
+        i16::from(old_pixel[2]) - i16::from(new_pixel[2]),
+    ]
+}
+
+fn generic_dithering(
+    img: &mut RgbImage,
+    diffusion_matrix: &[Coord],
+    factor: f64,
+    palette: &Palette,
+    serpentine: bool,
+) {
+    let (width, height) = img.dimensions();
+
+    for y in 0..height {
+        // On serpentine rows we traverse right-to-left and mirror every neighbor offset across
+        // the x axis, so error still flows in the direction of travel.
+        let reversed = serpentine && y % 2 == 1;
+        let xs: Box<dyn Iterator<Item = u32>> = if reversed {
+            Box::new((0..width).rev())
+        } else {
+            Box::new(0..width)
+        };
+
+        for x in xs {
+            let quant_err = scale_error(quantize_pixel(img, x, y, palette), factor);
+
+            for &Coord((delta_x, delta_y), (numerator, denominator)) in diffusion_matrix {
+                let delta_x = if reversed { -delta_x } else { delta_x };
+                if let (Some(new_x), Some(new_y)) =
+                    (x.checked_add_signed(delta_x), y.checked_add_signed(delta_y))
+                {
+                    diffuse_error_to_pixel(img, new_x, new_y, quant_err, numerator, denominator)
+                }
+            }
+        }
+    }
+}
+
+fn diffuse_error_to_pixel(
+    img: &mut RgbImage,
+    x: u32,
+    y: u32,
+    err: [i16; 3],
+    factor_numerator: i16,
+    factor_denominator: i16,
+) {
+    if img.in_bounds(x, y) {
+        let old_pixel = *img.get_pixel(x, y);
+        let new_pixel = Rgb([
+            coerce_to_u8(i16::from(old_pixel[0]) + err[0] * factor_numerator / factor_denominator),
+            coerce_to_u8(i16::from(old_pixel[1]) + err[1] * factor_numerator / factor_denominator),
+            coerce_to_u8(i16::from(old_pixel[2]) + err[2] * factor_numerator / factor_denominator),
+        ]);
+        img.put_pixel(x, y, new_pixel);
+    }
+}
+
+fn scale_error(err: [i16; 3], factor: f64) -> [i16; 3] {
+    [
+        (f64::from(err[0]) * factor).round() as i16,
+        (f64::from(err[1]) * factor).round() as i16,
+        (f64::from(err[2]) * factor).round() as i16,
+    ]
+}
+
+fn coerce_to_u8(i: i16) -> u8 {
+    if i > i16::from(u8::MAX) {
+        u8::MAX
+    } else if i < i16::from(u8::MIN) {
+        u8::MIN
+    } else {
+        i as u8
+    }
+}
+
+fn squared_distance(a: Rgb<u8>, b: Rgb<u8>) -> i32 {
+    (0..3)
+        .map(|c| {
+            let delta = i32::from(a[c]) - i32::from(b[c]);
+            delta * delta
+        })
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::RgbImage;
+
+    #[test]
+    fn bayer_matrix_4_matches_known_values() {
+        let expected = vec![
+            vec![0, 8, 2, 10],
+            vec![12, 4, 14, 6],
+            vec![3, 11, 1, 9],
+            vec![15, 7, 13, 5],
+        ];
+        assert_eq!(bayer_matrix(4), expected);
+    }
+
+    #[test]
+    fn from_levels_spaces_shades_evenly() {
+        let palette = Palette::from_levels(2);
+        assert_eq!(palette.0, vec![Rgb([0, 0, 0]), Rgb([255, 255, 255])]);
+
+        let palette = Palette::from_levels(3);
+        assert_eq!(
+            palette.0,
+            vec![Rgb([0, 0, 0]), Rgb([128, 128, 128]), Rgb([255, 255, 255])]
+        );
+    }
+
+    #[test]
+    fn nearest_picks_closest_entry() {
+        let palette = Palette::from_levels(2);
+        assert_eq!(palette.nearest(Rgb([100, 100, 100])), Rgb([0, 0, 0]));
+        assert_eq!(palette.nearest(Rgb([200, 200, 200])), Rgb([255, 255, 255]));
+    }
+
+    #[test]
+    fn parse_hex_color_accepts_valid_colors() {
+        assert_eq!(parse_hex_color("#ff0000"), Ok(Rgb([255, 0, 0])));
+        assert_eq!(parse_hex_color("00ff00"), Ok(Rgb([0, 255, 0])));
+    }
+
+    #[test]
+    fn parse_hex_color_rejects_malformed_input() {
+        assert!(parse_hex_color("#ff00").is_err());
+        assert!(parse_hex_color("#gggggg").is_err());
+    }
+
+    #[test]
+    fn palette_from_str_parses_a_list() {
+        let palette = "#000000,#ff0000,#ffffff".parse::<Palette>().unwrap();
+        assert_eq!(
+            palette.0,
+            vec![Rgb([0, 0, 0]), Rgb([255, 0, 0]), Rgb([255, 255, 255])]
+        );
+    }
+
+    #[test]
+    fn scale_error_with_zero_factor_discards_error() {
+        assert_eq!(scale_error([100, -50, 7], 0.0), [0, 0, 0]);
+        assert_eq!(scale_error([100, -50, 7], 1.0), [100, -50, 7]);
+    }
+
+    #[test]
+    fn factor_zero_reduces_diffusion_to_quantization() {
+        let gradient = RgbImage::from_fn(8, 8, |x, y| {
+            let v = ((x + y) * 16) as u8;
+            Rgb([v, v, v])
+        });
+        let source = DynamicImage::ImageRgb8(gradient);
+
+        let diffused = Ditherer::new(Mode::FloydSteinberg)
+            .factor(0.0)
+            .dither(&source);
+        let quantized = Ditherer::new(Mode::Quantization).dither(&source);
+
+        assert_eq!(diffused.to_rgb8(), quantized.to_rgb8());
+    }
+
+    #[test]
+    fn serpentine_mirrors_the_offset_on_odd_rows() {
+        // A single pixel of error on an odd (reversed) row should diffuse to the left rather than
+        // the right, since the Floyd-Steinberg (1, 0) offset is mirrored.
+        let mut img = RgbImage::from_pixel(4, 2, Rgb([0, 0, 0]));
+        img.put_pixel(2, 1, Rgb([128, 128, 128]));
+        // On its own (1, 1) would snap to white; only the error from (2, 1) can darken it.
+        img.put_pixel(1, 1, Rgb([150, 150, 150]));
+        let palette = Palette::from_levels(2);
+
+        let matrix = diffusion_matrix(Mode::FloydSteinberg);
+        generic_dithering(&mut img, &matrix, 1.0, &palette, true);
+
+        // (2, 1) snaps to white with an error of -127, and 7/16 of it (-55) lands on its left
+        // neighbor (1, 1), pulling 150 down to 95, which snaps to black.
+        assert_eq!(img.get_pixel(2, 1), &Rgb([255, 255, 255]));
+        assert_eq!(img.get_pixel(1, 1), &Rgb([0, 0, 0]));
+        // The right neighbor (3, 1) was scanned first and receives nothing.
+        assert_eq!(img.get_pixel(3, 1), &Rgb([0, 0, 0]));
+    }
+}